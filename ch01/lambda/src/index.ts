@@ -1,14 +1,29 @@
 import { Handler } from 'aws-lambda';
 
 interface Request {
-  name: string;
+  name?: string;
+  names?: string[];
 }
 
 interface Response {
-  greeting: string;
+  greeting?: string;
+  greetings?: string[];
 }
 
+// Upper bound on a bulk request so one invocation can't be made arbitrarily large
+const MAX_NAMES = 100;
+
 export const handler: Handler<Request, Response> = async (event) => {
+  if (event.names !== undefined) {
+    if (!Array.isArray(event.names) || event.names.length > MAX_NAMES) {
+      throw new Error(`names must be a list of at most ${MAX_NAMES} names`);
+    }
+
+    return {
+      greetings: event.names.map((name) => `hello ${name}`)
+    };
+  }
+
   const name = event.name;
 
   return {
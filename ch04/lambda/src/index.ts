@@ -17,6 +17,28 @@ const CLUSTER_ENDPOINT = process.env.CLUSTER_ENDPOINT || 'YOUR_CLUSTER_ENDPOINT'
 const REGION = process.env.REGION || 'us-west-2';
 const USER = 'admin';
 
+// Decimal places in the reported transaction_time; checked at load so a bad
+// value can't fail an invocation after its transaction has committed
+const LATENCY_PRECISION = Number(process.env.LATENCY_PRECISION || 3);
+
+if (!Number.isInteger(LATENCY_PRECISION) || LATENCY_PRECISION < 0 || LATENCY_PRECISION > 9) {
+  throw new Error('LATENCY_PRECISION must be an integer between 0 and 9');
+}
+
+// Test-only: lets payer_id == payee_id through, so both updates hit the same
+// row in one transaction and the balance should come back unchanged
+const ALLOW_SELF_TRANSFER = process.env.ALLOW_SELF_TRANSFER === 'true';
+
+// Initial connect is retried so a transient blip doesn't fail the cold start
+const INIT_CONNECT_ATTEMPTS = 5;
+const INIT_CONNECT_BASE_DELAY_MS = 100;
+
+// Surfaces as errorType `PayeeNotFoundError` so callers can count missing
+// payees separately from real failures
+class PayeeNotFoundError extends Error {
+  name = 'PayeeNotFoundError';
+}
+
 // Connection reuse - create once and reuse across invocations
 let cachedClient: Sql | null = null;
 
@@ -34,12 +56,29 @@ async function getPasswordToken(clusterEndpoint: string, user: string, region: s
   }
 }
 
+async function connectWithRetry(client: Sql): Promise<void> {
+  for (let attempt = 1; ; attempt++) {
+    try {
+      await client`SELECT 1`;
+      return;
+    } catch (error) {
+      if (attempt >= INIT_CONNECT_ATTEMPTS) {
+        throw error;
+      }
+
+      const delay = INIT_CONNECT_BASE_DELAY_MS * 2 ** (attempt - 1);
+      console.warn(`Connection attempt ${attempt}/${INIT_CONNECT_ATTEMPTS} failed, retrying in ${delay}ms:`, error);
+      await new Promise((resolve) => setTimeout(resolve, delay));
+    }
+  }
+}
+
 async function getConnection(clusterEndpoint: string, user: string, region: string): Promise<Sql> {
   if (cachedClient) {
     return cachedClient;
   }
 
-  cachedClient = postgres({
+  const client = postgres({
     host: clusterEndpoint,
     user: user,
     password: async () => await getPasswordToken(clusterEndpoint, user, region),
@@ -51,13 +90,22 @@ async function getConnection(clusterEndpoint: string, user: string, region: stri
     }
   });
 
+  try {
+    await connectWithRetry(client);
+  } catch (error) {
+    await client.end();
+    throw error;
+  }
+
+  cachedClient = client;
   return cachedClient;
 }
 
 export const handler: Handler<Request, Response> = async (event) => {
-  const start = Date.now();
+  // performance.now() has sub-millisecond resolution, unlike Date.now()
+  const start = performance.now();
 
-  if (event.payer_id === event.payee_id) {
+  if (event.payer_id === event.payee_id && !ALLOW_SELF_TRANSFER) {
     throw new Error('Payer and payee must be different accounts');
   }
 
@@ -84,23 +132,27 @@ export const handler: Handler<Request, Response> = async (event) => {
       }
 
       // Add to payee
-      const payeeResult = await sql`
+      const payeeRows = await sql`
         UPDATE accounts
         SET balance = balance + ${event.amount}
         WHERE id = ${event.payee_id}
+        RETURNING balance
       `;
 
-      if (payeeResult.count !== 1) {
-        throw new Error('Payee account not found');
+      if (payeeRows.length !== 1) {
+        throw new PayeeNotFoundError('Payee account not found');
       }
 
+      // On a self-transfer the credit lands on the payer's row after the debit
+      const finalBalance = event.payer_id === event.payee_id ? parseFloat(payeeRows[0].balance) : payerBalance;
+
       return {
-        payer_balance: payerBalance.toString()
+        payer_balance: finalBalance.toString()
       };
     });
 
-    const elapsed = Date.now() - start;
-    const transactionTime = `${elapsed.toFixed(3)}ms`;
+    const elapsed = performance.now() - start;
+    const transactionTime = `${elapsed.toFixed(LATENCY_PRECISION)}ms`;
 
     return {
       payer_balance: result.payer_balance,
@@ -2,9 +2,27 @@ import * as cdk from 'aws-cdk-lib';
 import * as lambda from 'aws-cdk-lib/aws-lambda';
 import * as nodejs from 'aws-cdk-lib/aws-lambda-nodejs';
 import * as iam from 'aws-cdk-lib/aws-iam';
+import * as events from 'aws-cdk-lib/aws-events';
+import * as targets from 'aws-cdk-lib/aws-events-targets';
 import { Construct } from 'constructs';
 import * as path from 'path';
 
+// Boolean context flag: unset means off. Accepts `true` or `false` in any case,
+// whether it comes from -c (a string) or cdk.json (a boolean), and rejects
+// anything else so a typo like `-c schemaCheck=yes` fails the synth
+function contextFlag(scope: Construct, name: string): boolean {
+  const value = scope.node.tryGetContext(name);
+  if (value === undefined) {
+    return false;
+  }
+
+  const normalized = String(value).toLowerCase();
+  if (normalized !== 'true' && normalized !== 'false') {
+    throw new Error(`${name} must be true or false, got ${JSON.stringify(value)}`);
+  }
+  return normalized === 'true';
+}
+
 export class Ch05Stack extends cdk.Stack {
   constructor(scope: Construct, id: string, props?: cdk.StackProps) {
     super(scope, id, props);
@@ -14,6 +32,33 @@ export class Ch05Stack extends cdk.Stack {
       throw new Error('clusterEndpoint must be provided via context or CLUSTER_ENDPOINT env var');
     }
 
+    // Optional role to assume for clusters in another account
+    const dsqlRoleArn = this.node.tryGetContext('dsqlRoleArn') || process.env.DSQL_ROLE_ARN;
+
+    // Optional CloudWatch namespace for the function's EMF metrics
+    const metricsNamespace = this.node.tryGetContext('metricsNamespace') || process.env.METRICS_NAMESPACE;
+
+    // Connection management strategy: 'single' or 'pool' (default)
+    const connectionModel = this.node.tryGetContext('connectionModel') || process.env.CONNECTION_MODEL;
+
+    // Account id width: 'int' (default) or 'bigint'
+    const accountIdType = this.node.tryGetContext('accountIdType') || process.env.ACCOUNT_ID_TYPE;
+
+    // Account credited with transfer fees; transfers with a fee need it
+    const feeAccountId = this.node.tryGetContext('feeAccountId') || process.env.FEE_ACCOUNT_ID;
+
+    // Fail fast with a setup message when the tables are missing
+    const schemaCheck = contextFlag(this, 'schemaCheck');
+
+    // Start with writes paused; usually toggled later on the live function instead
+    const maintenanceMode = contextFlag(this, 'maintenanceMode');
+
+    // Opt in to the EventBridge rule that executes due scheduled transfers
+    const scheduledTransfers = contextFlag(this, 'scheduledTransfers');
+
+    // Optional OTLP/HTTP collector for handler traces
+    const otlpEndpoint = this.node.tryGetContext('otlpEndpoint') || process.env.OTEL_EXPORTER_OTLP_ENDPOINT;
+
     const lambdaFunction = new nodejs.NodejsFunction(this, 'Ch05Function', {
       runtime: lambda.Runtime.NODEJS_20_X,
       entry: path.join(__dirname, '../../lambda/src/index.ts'),
@@ -23,11 +68,19 @@ export class Ch05Stack extends cdk.Stack {
       memorySize: 512,
       environment: {
         CLUSTER_ENDPOINT: clusterEndpoint,
-        REGION: this.region
+        REGION: this.region,
+        ...(dsqlRoleArn && { DSQL_ROLE_ARN: dsqlRoleArn }),
+        ...(metricsNamespace && { METRICS_NAMESPACE: metricsNamespace }),
+        ...(connectionModel && { CONNECTION_MODEL: connectionModel }),
+        ...(accountIdType && { ACCOUNT_ID_TYPE: accountIdType }),
+        ...(maintenanceMode && { MAINTENANCE_MODE: 'true' }),
+        ...(schemaCheck && { SCHEMA_CHECK: 'true' }),
+        ...(feeAccountId && { FEE_ACCOUNT_ID: String(feeAccountId) }),
+        ...(otlpEndpoint && { OTEL_EXPORTER_OTLP_ENDPOINT: otlpEndpoint })
       },
       bundling: {
         externalModules: ['aws-sdk'],
-        nodeModules: ['@aws-sdk/dsql-signer', 'postgres']
+        nodeModules: ['@aws-sdk/dsql-signer', '@aws-sdk/credential-providers', 'postgres']
       }
     });
 
@@ -41,6 +94,23 @@ export class Ch05Stack extends cdk.Stack {
       resources: ['*']
     }));
 
+    if (dsqlRoleArn) {
+      lambdaFunction.addToRolePolicy(new iam.PolicyStatement({
+        effect: iam.Effect.ALLOW,
+        actions: ['sts:AssumeRole'],
+        resources: [dsqlRoleArn]
+      }));
+    }
+
+    if (scheduledTransfers) {
+      new events.Rule(this, 'ScheduledTransfersRule', {
+        schedule: events.Schedule.rate(cdk.Duration.minutes(1)),
+        targets: [new targets.LambdaFunction(lambdaFunction, {
+          event: events.RuleTargetInput.fromObject({ action: 'run_scheduled' })
+        })]
+      });
+    }
+
     new cdk.CfnOutput(this, 'FunctionName', {
       value: lambdaFunction.functionName,
       description: 'Lambda Function Name'
@@ -1,32 +1,425 @@
 import { Handler } from 'aws-lambda';
 import { DsqlSigner } from '@aws-sdk/dsql-signer';
+import { fromTemporaryCredentials } from '@aws-sdk/credential-providers';
 import postgres, { Sql, PostgresError } from 'postgres';
+import { context, trace, SpanStatusCode } from '@opentelemetry/api';
+import { NodeTracerProvider, BatchSpanProcessor } from '@opentelemetry/sdk-trace-node';
+import { OTLPTraceExporter } from '@opentelemetry/exporter-trace-otlp-http';
+import { schemaStatements, REQUIRED_COLUMNS } from './schema.js';
 
-interface Request {
-  payer_id: number;
-  payee_id: number;
+// A number in `int` mode; in `bigint` mode ids past 2^53 must be sent as
+// decimal strings, and ids read back from the database are always strings
+type AccountId = number | string;
+
+export interface TransferRequest {
+  action?: 'transfer';
+  payer_id: AccountId;
+  payee_id: AccountId;
   amount: string;
+  // Abort unless the payer keeps at least this balance after the debit
+  min_balance?: string;
+  // Charged to the payer on top of amount and credited to FEE_ACCOUNT_ID
+  fee?: string;
+  detailed_timing?: boolean;
+  minor_units?: boolean;
+  include_balances?: boolean;
+  server_time?: boolean;
+  // Artificial delay before responding; only honored when CHAOS_ENABLED=true
+  delay_ms?: number;
+}
+
+// Resets every account to the same balance so runs start from a known state
+interface ResetRequest {
+  action: 'reset';
+  opening_balance: string;
+  confirm: boolean;
+}
+
+// Most recent transfers in which the account was payer or payee
+interface HistoryRequest {
+  action: 'history';
+  id: AccountId;
+  limit?: number;
+}
+
+// Queues a transfer to run once execute_at has passed
+interface ScheduleRequest {
+  action: 'schedule';
+  payer_id: AccountId;
+  payee_id: AccountId;
+  amount: string;
+  execute_at: string;
+}
+
+// Sent by the EventBridge rule to execute every due scheduled transfer
+interface RunScheduledRequest {
+  action: 'run_scheduled';
+}
+
+// Pages through existing account ids so clients can sample real accounts
+interface AccountsRequest {
+  action: 'accounts';
+  after?: AccountId;
+  limit?: number;
+}
+
+// Sets exact balances on specific accounts, all-or-nothing, for test fixtures
+interface SetBalancesRequest {
+  action: 'set_balances';
+  balances: { id: AccountId; balance: string }[];
+}
+
+// Undoes a recorded transfer by moving the amount back from payee to payer
+interface ReverseRequest {
+  action: 'reverse';
+  transfer_id: string;
+}
+
+// Current balance of one account; served even in maintenance mode
+interface BalanceRequest {
+  action: 'balance';
+  id: AccountId;
+}
+
+// Opens the pool to its full size before a measured run
+interface WarmupRequest {
+  action: 'warmup';
+}
+
+// Creates any missing ch05 tables and indexes; existing ones are left as is
+interface InitSchemaRequest {
+  action: 'init_schema';
+}
+
+type Request = TransferRequest | ResetRequest | HistoryRequest | ScheduleRequest | RunScheduledRequest | AccountsRequest | SetBalancesRequest | ReverseRequest | BalanceRequest | WarmupRequest | InitSchemaRequest;
+type Action = NonNullable<Request['action']>;
+
+// Milliseconds spent in each phase, summed across OCC attempts
+interface TransactionTiming {
+  connection_ms: number;
+  query_ms: number;
+  commit_ms: number;
 }
 
-interface Response {
+interface TransferResponse {
+  transfer_id: string;
   payer_balance: string;
   transaction_time: string;
   attempts: number;
+  // Time spent waiting for a connection (and BEGIN), summed across attempts
+  connection_wait_ms: number;
+  timing?: TransactionTiming;
+  // Integer balance in minor units (e.g. cents) and the number of decimal places it implies
+  payer_balance_minor?: number;
+  scale?: number;
+  // Payer balance around the transfer, as exact NUMERIC text
+  payer_balance_before?: string;
+  payer_balance_after?: string;
+  // Cluster clock at the start of the committed transaction (ISO 8601)
+  server_time?: string;
+}
+
+interface ResetResponse {
+  rows_reset: number;
+}
+
+interface TransferRecord {
+  id: string;
+  payer_id: AccountId;
+  payee_id: AccountId;
+  amount: string;
+  created_at: string;
+}
+
+interface HistoryResponse {
+  transfers: TransferRecord[];
+}
+
+interface ScheduleResponse {
+  scheduled_id: string;
+  execute_at: string;
+}
+
+interface RunScheduledResponse {
+  executed: number;
+  failed: number;
+  skipped: number;
+}
+
+interface AccountsResponse {
+  ids: AccountId[];
+  // Pass as `after` to fetch the next page; absent on the last page
+  next_after?: AccountId;
+}
+
+interface SetBalancesResponse {
+  rows_updated: number;
+  attempts: number;
+}
+
+interface ReverseResponse {
+  reversal_id: string;
+  original_id: string;
+  // Balance of the account refunding the amount (the original payee)
+  payer_balance: string;
+  attempts: number;
+}
+
+interface BalanceResponse {
+  id: AccountId;
+  balance: string;
+}
+
+interface WarmupResponse {
+  target_depth: number;
+  // Connections that answered `SELECT 1`
+  reached_depth: number;
+  warmup_ms: number;
 }
 
+interface InitSchemaResponse {
+  // Tables that did not exist before this call
+  created: string[];
+}
+
+type Response = TransferResponse | ResetResponse | HistoryResponse | ScheduleResponse | RunScheduledResponse | AccountsResponse | SetBalancesResponse | ReverseResponse | BalanceResponse | WarmupResponse | InitSchemaResponse;
+
 const CLUSTER_ENDPOINT = process.env.CLUSTER_ENDPOINT || 'YOUR_CLUSTER_ENDPOINT';
 const REGION = process.env.REGION || 'us-west-2';
 const USER = 'admin';
+const DSQL_ROLE_ARN = process.env.DSQL_ROLE_ARN;
+const METRICS_NAMESPACE = process.env.METRICS_NAMESPACE;
+const CURRENCY_SCALE = Number(process.env.CURRENCY_SCALE || 2);
+const CHAOS_ENABLED = process.env.CHAOS_ENABLED === 'true';
+// Below the stack's 30s function timeout with room for the transaction, so a
+// delayed transfer still responds late rather than ending in "Task timed out"
+const MAX_DELAY_MS = 25000;
+
+// NUMERIC(18,6) balances stay below 10^12, so at most 3 decimal places keeps
+// every minor-unit balance below 2^53, where a JS number is still exact
+const MAX_CURRENCY_SCALE = 3;
+
+if (!Number.isInteger(CURRENCY_SCALE) || CURRENCY_SCALE < 0 || CURRENCY_SCALE > MAX_CURRENCY_SCALE) {
+  throw new Error(`CURRENCY_SCALE must be an integer between 0 and ${MAX_CURRENCY_SCALE}`);
+}
+
+// Decimal places in the reported transaction_time; checked at load so a bad
+// value can't fail an invocation after its transaction has committed
+const LATENCY_PRECISION = Number(process.env.LATENCY_PRECISION || 3);
+
+if (!Number.isInteger(LATENCY_PRECISION) || LATENCY_PRECISION < 0 || LATENCY_PRECISION > 9) {
+  throw new Error('LATENCY_PRECISION must be an integer between 0 and 9');
+}
+
+// Test-only: lets payer_id == payee_id through, so both updates hit the same
+// row in one transaction and the balance should come back unchanged
+const ALLOW_SELF_TRANSFER = process.env.ALLOW_SELF_TRANSFER === 'true';
+
+// Rejects every write action while still serving reads, so writes can be
+// quiesced during a migration by changing only the function configuration
+const MAINTENANCE_MODE = process.env.MAINTENANCE_MODE === 'true';
+const READ_ACTIONS: Action[] = ['history', 'accounts', 'balance', 'warmup'];
+
+// Account credited with transfer fees; requests with `fee` are rejected when unset
+// (checked against ACCOUNT_ID_TYPE below, once the id ranges are defined)
+const FEE_ACCOUNT_ID = process.env.FEE_ACCOUNT_ID;
+
+// Verify the schema once per container before the first action, so a fresh
+// cluster fails with one setup message instead of a raw error per invocation
+const SCHEMA_CHECK = process.env.SCHEMA_CHECK === 'true';
+
+// Width of accounts.id: 'int' (INT, the setup.sql schema) or 'bigint' (BIGINT,
+// for account universes past ~2.1 billion ids)
+const ACCOUNT_ID_TYPES = ['int', 'bigint'];
+const ACCOUNT_ID_TYPE = process.env.ACCOUNT_ID_TYPE || 'int';
+
+if (!ACCOUNT_ID_TYPES.includes(ACCOUNT_ID_TYPE)) {
+  throw new Error(`ACCOUNT_ID_TYPE must be one of: ${ACCOUNT_ID_TYPES.join(', ')} (UUID ids are handled by ch06)`);
+}
+
+// Auth token lifetime and how long a connection lives before it is replaced
+// (and a fresh token minted), both in seconds; unset keeps the library defaults
+const TOKEN_EXPIRES_IN = process.env.TOKEN_EXPIRES_IN ? Number(process.env.TOKEN_EXPIRES_IN) : undefined;
+const CONNECTION_MAX_LIFETIME = process.env.CONNECTION_MAX_LIFETIME ? Number(process.env.CONNECTION_MAX_LIFETIME) : undefined;
+
+for (const [name, value] of Object.entries({ TOKEN_EXPIRES_IN, CONNECTION_MAX_LIFETIME })) {
+  if (value !== undefined && (!Number.isInteger(value) || value <= 0)) {
+    throw new Error(`${name} must be a positive integer number of seconds`);
+  }
+}
+
+// Export spans over OTLP/HTTP when an endpoint is configured; otherwise no
+// provider is registered and every span below is a no-op
+const tracerProvider = process.env.OTEL_EXPORTER_OTLP_ENDPOINT ? new NodeTracerProvider() : undefined;
+if (tracerProvider) {
+  tracerProvider.addSpanProcessor(new BatchSpanProcessor(new OTLPTraceExporter()));
+  tracerProvider.register();
+}
+const tracer = trace.getTracer('ch05');
+
+// Assume a role before signing when the cluster lives in another account
+const credentials = DSQL_ROLE_ARN
+  ? fromTemporaryCredentials({
+      params: {
+        RoleArn: DSQL_ROLE_ARN,
+        RoleSessionName: 'ch05-dsql',
+      },
+      clientConfig: { region: REGION },
+    })
+  : undefined;
+
+// Fields accepted by each action, in addition to `action` itself
+const ACTION_FIELDS: Record<Action, { required: string[]; optional: string[] }> = {
+  transfer: { required: ['payer_id', 'payee_id', 'amount'], optional: ['min_balance', 'fee', 'detailed_timing', 'minor_units', 'include_balances', 'server_time', 'delay_ms'] },
+  reset: { required: ['opening_balance', 'confirm'], optional: [] },
+  history: { required: ['id'], optional: ['limit'] },
+  schedule: { required: ['payer_id', 'payee_id', 'amount', 'execute_at'], optional: [] },
+  run_scheduled: { required: [], optional: [] },
+  accounts: { required: [], optional: ['after', 'limit'] },
+  set_balances: { required: ['balances'], optional: [] },
+  reverse: { required: ['transfer_id'], optional: [] },
+  balance: { required: ['id'], optional: [] },
+  warmup: { required: [], optional: [] },
+  init_schema: { required: [], optional: [] },
+};
+
+// Account id fields checked against ACCOUNT_ID_TYPE
+const ACCOUNT_ID_FIELDS = ['payer_id', 'payee_id', 'id', 'after'];
+const ACCOUNT_ID_RANGES: Record<string, [bigint, bigint]> = {
+  int: [-(2n ** 31n), 2n ** 31n - 1n],
+  bigint: [-(2n ** 63n), 2n ** 63n - 1n],
+};
+// Canonical decimal form only, so equal ids always have equal strings
+const ACCOUNT_ID_PATTERN = /^(0|-?[1-9]\d{0,18})$/;
+
+// Same rules as a request id, so a fee account outside the column's range
+// stops the function at load instead of failing every fee transfer with 22003
+if (FEE_ACCOUNT_ID !== undefined && !isAccountIdInRange(FEE_ACCOUNT_ID)) {
+  throw new Error(`FEE_ACCOUNT_ID must be an ${ACCOUNT_ID_TYPE} account id`);
+}
+
+const UUID_PATTERN = /^[0-9a-f]{8}-[0-9a-f]{4}-[0-9a-f]{4}-[0-9a-f]{4}-[0-9a-f]{12}$/i;
+
+// Money fields and whether zero is acceptable; values must fit NUMERIC(18,6)
+const AMOUNT_FIELDS: Record<string, { allowZero: boolean }> = {
+  amount: { allowZero: false },
+  opening_balance: { allowZero: true },
+  min_balance: { allowZero: true },
+  fee: { allowZero: true },
+};
+const AMOUNT_PATTERN = /^\d{1,12}(\.\d{1,6})?$/;
+
+const DEFAULT_HISTORY_LIMIT = 10;
+const MAX_HISTORY_LIMIT = 100;
+
+const DEFAULT_ACCOUNTS_LIMIT = 1000;
+const MAX_ACCOUNTS_LIMIT = 10000;
+
+// DSQL limits how many rows one transaction may modify, so resets run in batches
+const RESET_BATCH_SIZE = 1000;
+
+// Due scheduled transfers picked up per EventBridge run
+const SCHEDULED_BATCH_SIZE = 100;
+
+// Client errors surface with errorType `ValidationError` in the Lambda response
+class ValidationError extends Error {
+  name = 'ValidationError';
+}
+
+class AlreadyReversedError extends Error {
+  name = 'AlreadyReversedError';
+}
+
+class InsufficientBalanceError extends Error {
+  name = 'InsufficientBalanceError';
+}
+
+class MinimumBalanceError extends Error {
+  name = 'MinimumBalanceError';
+}
+
+class PayerNotFoundError extends Error {
+  name = 'PayerNotFoundError';
+}
+
+// Random-id workloads against a sparse table hit missing payees routinely, so
+// they get their own errorType instead of a generic Error
+class PayeeNotFoundError extends Error {
+  name = 'PayeeNotFoundError';
+}
+
+class StatementTimeoutError extends Error {
+  name = 'StatementTimeoutError';
+}
+
+// Retriable: the same request succeeds once maintenance mode is switched off
+class MaintenanceModeError extends Error {
+  name = 'MaintenanceModeError';
+}
+
+class SchemaError extends Error {
+  name = 'SchemaError';
+}
+
+class BalanceOverflowError extends Error {
+  name = 'BalanceOverflowError';
+}
+
+// 'single' funnels every statement through one connection; 'pool' lets
+// postgres.js open up to POOL_SIZE connections on demand
+const CONNECTION_MODELS = ['single', 'pool'];
+const CONNECTION_MODEL = process.env.CONNECTION_MODEL || 'pool';
+const POOL_SIZE = Number(process.env.POOL_SIZE || 10);
+
+if (!CONNECTION_MODELS.includes(CONNECTION_MODEL)) {
+  throw new Error(`CONNECTION_MODEL must be one of: ${CONNECTION_MODELS.join(', ')}`);
+}
+
+if (!Number.isInteger(POOL_SIZE) || POOL_SIZE <= 0) {
+  throw new Error('POOL_SIZE must be a positive integer');
+}
+
+// Seconds before postgres.js closes an idle connection; 0 keeps idle
+// connections open, so a warm container reuses its connection after a pause
+const IDLE_TIMEOUT = Number(process.env.IDLE_TIMEOUT || 2);
+
+if (!Number.isInteger(IDLE_TIMEOUT) || IDLE_TIMEOUT < 0) {
+  throw new Error('IDLE_TIMEOUT must be a non-negative integer number of seconds');
+}
+
+// Server-side cap on any single statement; unset leaves it unlimited
+const STATEMENT_TIMEOUT_MS = process.env.STATEMENT_TIMEOUT_MS ? Number(process.env.STATEMENT_TIMEOUT_MS) : undefined;
+
+if (STATEMENT_TIMEOUT_MS !== undefined && (!Number.isInteger(STATEMENT_TIMEOUT_MS) || STATEMENT_TIMEOUT_MS <= 0)) {
+  throw new Error('STATEMENT_TIMEOUT_MS must be a positive integer number of milliseconds');
+}
+
+// Log the plans of the account statements once per container, to confirm DSQL
+// looks rows up through the accounts primary key rather than scanning
+const EXPLAIN_ON_STARTUP = process.env.EXPLAIN_ON_STARTUP === 'true';
+
+// Initial connect is retried so a transient blip doesn't fail the cold start
+const INIT_CONNECT_ATTEMPTS = 5;
+const INIT_CONNECT_BASE_DELAY_MS = 100;
 
 // Connection reuse - create once and reuse across invocations
 let cachedClient: Sql | null = null;
+let tokensGenerated = 0;
+let schemaChecked = false;
 
 async function getPasswordToken(clusterEndpoint: string, user: string, region: string): Promise<string> {
   const signer = new DsqlSigner({
     hostname: clusterEndpoint,
     region,
+    credentials,
+    expiresIn: TOKEN_EXPIRES_IN,
   });
 
+  // One mint per connection opened: the first connect, pool growth, idle or
+  // max_lifetime replacement, and reconnects after errors all count
+  tokensGenerated++;
+  console.log(`Generating DSQL auth token #${tokensGenerated}`);
+
   if (user === 'admin') {
     return await signer.getDbConnectAdminAuthToken();
   } else {
@@ -35,111 +428,977 @@ async function getPasswordToken(clusterEndpoint: string, user: string, region: s
   }
 }
 
+async function connectWithRetry(client: Sql): Promise<void> {
+  for (let attempt = 1; ; attempt++) {
+    try {
+      await client`SELECT 1`;
+      return;
+    } catch (error) {
+      if (attempt >= INIT_CONNECT_ATTEMPTS) {
+        throw error;
+      }
+
+      const delay = INIT_CONNECT_BASE_DELAY_MS * 2 ** (attempt - 1);
+      console.warn(`Connection attempt ${attempt}/${INIT_CONNECT_ATTEMPTS} failed, retrying in ${delay}ms:`, error);
+      await new Promise((resolve) => setTimeout(resolve, delay));
+    }
+  }
+}
+
+// Plain EXPLAIN plans without executing, so the UPDATE touches no rows. The
+// debit is the statement transfers run, with sample values bound to its parameters
+async function logQueryPlans(client: Sql): Promise<void> {
+  type PlanRow = { 'QUERY PLAN': string };
+  const sampleDebit: TransferRequest = { payer_id: 1, payee_id: 2, amount: '1' };
+  const statements: Record<string, () => Promise<PlanRow[]>> = {
+    debit_payer: () => client<PlanRow[]>`EXPLAIN ${debitStatement(client, sampleDebit)}`,
+    read_balance: () => client<PlanRow[]>`EXPLAIN SELECT balance FROM accounts WHERE id = 1`,
+  };
+
+  for (const [name, explain] of Object.entries(statements)) {
+    try {
+      const rows = await explain();
+      const plan = rows.map((row) => row['QUERY PLAN']).join('\n');
+      console.log(`Query plan for ${name}:\n${plan}`);
+    } catch (error) {
+      console.warn(`EXPLAIN for ${name} failed:`, error);
+    }
+  }
+}
+
 async function getConnection(clusterEndpoint: string, user: string, region: string): Promise<Sql> {
   if (cachedClient) {
     return cachedClient;
   }
 
-  cachedClient = postgres({
+  const client = postgres({
     host: clusterEndpoint,
     user: user,
     password: async () => await getPasswordToken(clusterEndpoint, user, region),
     database: 'postgres',
     port: 5432,
-    idle_timeout: 2,
+    max: CONNECTION_MODEL === 'single' ? 1 : POOL_SIZE,
+    idle_timeout: IDLE_TIMEOUT,
+    // postgres.js treats a present-but-undefined option as "off", so only pass it when set
+    ...(CONNECTION_MAX_LIFETIME !== undefined && { max_lifetime: CONNECTION_MAX_LIFETIME }),
     ssl: {
       rejectUnauthorized: true,
-    }
+    },
+    // Sent as a startup parameter, so it applies to every pooled connection
+    ...(STATEMENT_TIMEOUT_MS !== undefined && { connection: { statement_timeout: STATEMENT_TIMEOUT_MS } })
   });
 
+  try {
+    await connectWithRetry(client);
+  } catch (error) {
+    await client.end();
+    throw error;
+  }
+
+  if (EXPLAIN_ON_STARTUP) {
+    await logQueryPlans(client);
+  }
+
+  cachedClient = client;
   return cachedClient;
 }
 
+function validateAmount(field: string, value: unknown, allowZero: boolean): void {
+  const text = typeof value === 'number' ? String(value) : value;
+
+  if (typeof text !== 'string' || !AMOUNT_PATTERN.test(text)) {
+    throw new ValidationError(
+      `Invalid amount: field '${field}' must be a decimal with at most 12 integer and 6 fractional digits, received ${JSON.stringify(value)}`
+    );
+  }
+
+  if (!allowZero && Number(text) === 0) {
+    throw new ValidationError(`Invalid amount: field '${field}' must be greater than zero, received ${JSON.stringify(value)}`);
+  }
+}
+
+// Canonical decimal text within the range of ACCOUNT_ID_TYPE
+function isAccountIdInRange(text: string): boolean {
+  const [min, max] = ACCOUNT_ID_RANGES[ACCOUNT_ID_TYPE];
+  return ACCOUNT_ID_PATTERN.test(text) && BigInt(text) >= min && BigInt(text) <= max;
+}
+
+function validateAccountId(field: string, value: unknown): void {
+  const text = typeof value === 'number' && Number.isSafeInteger(value) ? String(value) : value;
+
+  // Strings are only accepted in bigint mode, where ids can exceed 2^53
+  const valid = typeof text === 'string' && isAccountIdInRange(text)
+    && (typeof value === 'number' || ACCOUNT_ID_TYPE === 'bigint');
+
+  if (!valid) {
+    throw new ValidationError(`Invalid account id: field '${field}' must be an ${ACCOUNT_ID_TYPE} id, received ${JSON.stringify(value)}`);
+  }
+}
+
+// Orders ids numerically; string ids can't be compared with `<`
+function compareAccountIds(a: AccountId, b: AccountId): number {
+  const difference = BigInt(a) - BigInt(b);
+  return difference < 0n ? -1 : difference > 0n ? 1 : 0;
+}
+
+function validateRequest(event: Request): void {
+  // Checked before any field is read, so a null, string or array payload gets
+  // a ValidationError rather than a TypeError or a list of character indexes
+  if (typeof event !== 'object' || event === null || Array.isArray(event)) {
+    const received = event === null ? 'null' : Array.isArray(event) ? 'an array' : `a ${typeof event}`;
+    throw new ValidationError(`Request must be a JSON object, received ${received}`);
+  }
+
+  const action = event.action ?? 'transfer';
+  if (!Object.hasOwn(ACTION_FIELDS, action)) {
+    throw new ValidationError(`Unknown action: ${action}`);
+  }
+
+  const { required, optional } = ACTION_FIELDS[action];
+  const allowedFields = ['action', ...required, ...optional];
+
+  const unknownFields = Object.keys(event).filter((field) => !allowedFields.includes(field));
+  if (unknownFields.length > 0) {
+    throw new ValidationError(`Unknown field(s) in request: ${unknownFields.join(', ')}`);
+  }
+
+  const missingFields = required.filter((field) => (event as Record<string, unknown>)[field] === undefined);
+  if (missingFields.length > 0) {
+    throw new ValidationError(`Missing field(s) in request: ${missingFields.join(', ')}`);
+  }
+
+  for (const [field, { allowZero }] of Object.entries(AMOUNT_FIELDS)) {
+    if (field in event) {
+      validateAmount(field, (event as Record<string, unknown>)[field], allowZero);
+    }
+  }
+
+  for (const field of ACCOUNT_ID_FIELDS) {
+    if (field in event) {
+      validateAccountId(field, (event as Record<string, unknown>)[field]);
+    }
+  }
+}
+
+// Publish latency and OCC retries through the CloudWatch Embedded Metric Format
+function emitTransferMetrics(latencyMs: number, attempts: number): void {
+  if (!METRICS_NAMESPACE) {
+    return;
+  }
+
+  console.log(JSON.stringify({
+    _aws: {
+      Timestamp: Date.now(),
+      CloudWatchMetrics: [{
+        Namespace: METRICS_NAMESPACE,
+        Dimensions: [['FunctionName']],
+        Metrics: [
+          { Name: 'TransactionTime', Unit: 'Milliseconds' },
+          { Name: 'OccRetries', Unit: 'Count' }
+        ]
+      }]
+    },
+    FunctionName: process.env.AWS_LAMBDA_FUNCTION_NAME,
+    TransactionTime: latencyMs,
+    OccRetries: attempts - 1
+  }));
+}
+
+// Convert NUMERIC text to integer minor units without going through floats,
+// rounding half away from zero at `scale` decimal places
+function toMinorUnits(value: string, scale: number): number {
+  const match = /^(-?)(\d+)(?:\.(\d+))?$/.exec(value);
+  if (!match) {
+    throw new Error(`Cannot convert balance to minor units: ${value}`);
+  }
+
+  const [, sign, whole, fraction = ''] = match;
+  const truncated = BigInt(whole + fraction.padEnd(scale, '0').slice(0, scale));
+  const minor = fraction.length > scale && fraction[scale] >= '5' ? truncated + 1n : truncated;
+
+  return Number(sign ? -minor : minor);
+}
+
+function isStatementTimeout(error: any): boolean {
+  // PostgreSQL query_canceled, raised when statement_timeout fires
+  return error?.code === '57014';
+}
+
+function isNumericOverflow(error: any): boolean {
+  // PostgreSQL numeric_value_out_of_range, e.g. a credit pushing a balance
+  // past what NUMERIC(18,6) can hold
+  return error?.code === '22003';
+}
+
+// Replace database errors that have a clear client-facing meaning; anything
+// else is passed through unchanged
+function toClientError(error: any): unknown {
+  let mapped: any;
+
+  // 57014 is also raised by cancellation and server-side limits, so it only
+  // means our timeout fired when one is configured
+  if (STATEMENT_TIMEOUT_MS !== undefined && isStatementTimeout(error)) {
+    mapped = new StatementTimeoutError(`Statement exceeded STATEMENT_TIMEOUT_MS (${STATEMENT_TIMEOUT_MS}ms)`);
+  } else if (isNumericOverflow(error)) {
+    mapped = new BalanceOverflowError('Balance would exceed the NUMERIC(18,6) range of the accounts table');
+  } else {
+    mapped = error;
+  }
+
+  // withOccRetry keeps the failure breakdown in properties; it only becomes
+  // part of the message here, so classification upstream sees the original
+  if (error?.failureBreakdown) {
+    mapped.message += ` (after ${error.attempts} attempts: ${error.failureBreakdown})`;
+  }
+  return mapped;
+}
+
+// Failures that would recur on every retry of the same transfer. Anything
+// else (connection loss, timeouts, expired tokens) may clear up by itself
+function isBusinessError(error: any): boolean {
+  return error instanceof PayerNotFoundError
+    || error instanceof PayeeNotFoundError
+    || error instanceof InsufficientBalanceError
+    || error instanceof MinimumBalanceError
+    || isNumericOverflow(error);
+}
+
 function isOccError(error: any): boolean {
   // PostgreSQL serialization failure error code
   return error?.code === '40001';
 }
 
-async function executeTransfer(sql: Sql, request: Request): Promise<string> {
-  // Deduct from payer and check balance
-  const payerRows = await sql`
+interface TransferResult {
+  transferId: string;
+  payerBalance: string;
+  // Exact NUMERIC text as returned by the database
+  payerBalanceNumeric: string;
+  payerBalanceBeforeNumeric: string;
+  serverTime?: string;
+}
+
+interface DebitResult {
+  balance: string;
+  previousBalance: string;
+}
+
+// The debit statement, unexecuted, so logQueryPlans can EXPLAIN the same SQL
+function debitStatement(sql: Sql, request: TransferRequest) {
+  // The floor is compared in SQL so the check is exact NUMERIC arithmetic
+  // The payer covers the fee too, so the balance checks apply to amount + fee
+  // Amounts and fees have at most 6 decimal places, so adding them back is
+  // exact NUMERIC arithmetic and gives the stored previous balance without a
+  // second lookup of the row
+  const fee = request.fee ?? 0;
+  return sql`
     UPDATE accounts
-    SET balance = balance - ${request.amount}
+    SET balance = balance - ${request.amount} - ${fee}
     WHERE id = ${request.payer_id}
-    RETURNING balance
+    RETURNING
+      balance,
+      balance + ${request.amount} + ${fee} AS previous_balance,
+      balance >= ${request.min_balance ?? 0} AS meets_min_balance
   `;
+}
+
+// Deduct from payer and check balance; balances are returned as NUMERIC text
+async function debitPayer(sql: Sql, request: TransferRequest): Promise<DebitResult> {
+  const payerRows = await debitStatement(sql, request);
 
   if (payerRows.length === 0) {
-    throw new Error('Payer account not found');
+    throw new PayerNotFoundError('Payer account not found');
   }
 
   const payerBalance = parseFloat(payerRows[0].balance);
   if (payerBalance < 0) {
-    throw new Error(`Insufficient balance: ${payerBalance}`);
+    throw new InsufficientBalanceError(`Insufficient balance: ${payerBalance}`);
   }
 
-  // Add to payee
-  const payeeResult = await sql`
+  if (!payerRows[0].meets_min_balance) {
+    throw new MinimumBalanceError(`Balance after transfer (${payerBalance}) would fall below min_balance ${request.min_balance}`);
+  }
+
+  return {
+    balance: payerRows[0].balance,
+    previousBalance: payerRows[0].previous_balance
+  };
+}
+
+// Add to payee
+async function creditPayee(sql: Sql, request: TransferRequest): Promise<string> {
+  const payeeRows = await sql`
     UPDATE accounts
     SET balance = balance + ${request.amount}
     WHERE id = ${request.payee_id}
+    RETURNING balance
   `;
 
-  if (payeeResult.count !== 1) {
-    throw new Error('Payee account not found');
+  if (payeeRows.length !== 1) {
+    throw new PayeeNotFoundError('Payee account not found');
   }
 
-  return payerBalance.toString();
+  return payeeRows[0].balance;
 }
 
-export const handler: Handler<Request, Response> = async (event) => {
-  const start = Date.now();
+async function creditFeeAccount(sql: Sql, fee: string): Promise<void> {
+  const feeResult = await sql`
+    UPDATE accounts
+    SET balance = balance + ${fee}
+    WHERE id = ${FEE_ACCOUNT_ID!}
+  `;
+
+  if (feeResult.count !== 1) {
+    throw new Error(`Fee account ${FEE_ACCOUNT_ID} not found`);
+  }
+}
 
-  if (event.payer_id === event.payee_id) {
+// `reversalOf` links the ledger entry to the transfer it undoes. Exported,
+// like withOccRetry, so test/conservation.ts can drive it with a plain client
+export async function executeTransfer(sql: Sql, request: TransferRequest, reversalOf?: string): Promise<TransferResult> {
+  const debit = await debitPayer(sql, request);
+  const payeeBalance = await creditPayee(sql, request);
+  if (request.fee !== undefined) {
+    await creditFeeAccount(sql, request.fee);
+  }
+
+  // On a self-transfer the credit lands on the payer's row after the debit
+  const payerBalanceAfter = compareAccountIds(request.payer_id, request.payee_id) === 0 ? payeeBalance : debit.balance;
+
+  // Record the movement in the ledger as part of the same transaction
+  const transferRows = await sql`
+    INSERT INTO transfers (payer_id, payee_id, amount, fee, reversal_of)
+    VALUES (${request.payer_id}, ${request.payee_id}, ${request.amount}, ${request.fee ?? null}, ${reversalOf ?? null})
+    RETURNING id
+  `;
+
+  return {
+    transferId: transferRows[0].id,
+    payerBalance: parseFloat(payerBalanceAfter).toString(),
+    payerBalanceNumeric: payerBalanceAfter,
+    payerBalanceBeforeNumeric: debit.previousBalance
+  };
+}
+
+interface RetryOutcome<T> {
+  result: T;
+  attempts: number;
+}
+
+// Record an attempt and its phases as spans, after the fact, from the measured boundaries
+function recordAttemptSpans(attempt: number, outcome: 'committed' | 'occ_conflict' | 'error', phases: number[]): void {
+  const [attemptStart, queryStart, queryEnd, attemptEnd] = phases;
+
+  const attemptSpan = tracer.startSpan('transaction', {
+    startTime: attemptStart,
+    attributes: { 'occ.attempt': attempt, 'occ.outcome': outcome }
+  });
+  if (outcome !== 'committed') {
+    attemptSpan.setStatus({ code: SpanStatusCode.ERROR, message: outcome });
+  }
+
+  const attemptContext = trace.setSpan(context.active(), attemptSpan);
+  tracer.startSpan('connection', { startTime: attemptStart }, attemptContext).end(queryStart);
+  tracer.startSpan('query', { startTime: queryStart }, attemptContext).end(queryEnd);
+  tracer.startSpan('commit', { startTime: queryEnd }, attemptContext).end(attemptEnd);
+
+  attemptSpan.end(attemptEnd);
+}
+
+// Run `work` in a transaction, retrying from scratch on OCC conflicts. When
+// `timing` is given, each attempt's phases are added to it
+export async function withOccRetry<T>(client: Sql, work: (sql: Sql) => Promise<T>, timing?: TransactionTiming): Promise<RetryOutcome<T>> {
+  let attempts = 0;
+  // Failure reasons seen so far, keyed by SQLSTATE (or error name for errors
+  // raised by the handler itself), so a final failure can say what preceded it
+  const failureReasons: Record<string, number> = {};
+
+  while (true) {
+    attempts++;
+
+    // Phase boundaries: begin() runs the callback once a connection is
+    // reserved and BEGIN has run, and resolves itself after COMMIT
+    const attemptStart = Date.now();
+    let queryStart: number | undefined;
+    let queryEnd: number | undefined;
+    let outcome: 'committed' | 'occ_conflict' | 'error' = 'error';
+
+    try {
+      const result = await client.begin(async (sql) => {
+        queryStart = Date.now();
+        const workResult = await work(sql);
+        queryEnd = Date.now();
+        return workResult;
+      }) as T;
+
+      // Transaction committed successfully
+      outcome = 'committed';
+      return { result, attempts };
+    } catch (error: any) {
+      const reason = error?.code ?? error?.name ?? 'unknown';
+      failureReasons[reason] = (failureReasons[reason] ?? 0) + 1;
+
+      // Check if this is an OCC error (serialization failure)
+      if (isOccError(error)) {
+        // Retry on OCC error
+        outcome = 'occ_conflict';
+        continue;
+      }
+
+      // For non-OCC errors, rethrow, with the breakdown when earlier attempts
+      // failed for other reasons
+      if (attempts > 1) {
+        const breakdown = Object.entries(failureReasons).map(([key, count]) => `${key} x${count}`).join(', ');
+        console.error(JSON.stringify({ message: 'Transaction failed', attempts, failure_reasons: failureReasons }));
+        if (error instanceof Error) {
+          // Kept on the error, so toClientError can add it to the message
+          (error as any).failureBreakdown = breakdown;
+          (error as any).attempts = attempts;
+        }
+      }
+      throw error;
+    } finally {
+      const attemptEnd = Date.now();
+      queryStart ??= attemptEnd;
+      queryEnd ??= attemptEnd;
+
+      if (timing) {
+        timing.connection_ms += queryStart - attemptStart;
+        timing.query_ms += queryEnd - queryStart;
+        timing.commit_ms += attemptEnd - queryEnd;
+      }
+
+      recordAttemptSpans(attempts, outcome, [attemptStart, queryStart, queryEnd, attemptEnd]);
+    }
+  }
+}
+
+async function transfer(event: TransferRequest): Promise<TransferResponse> {
+  // performance.now() has sub-millisecond resolution, unlike Date.now()
+  const start = performance.now();
+
+  if (compareAccountIds(event.payer_id, event.payee_id) === 0 && !ALLOW_SELF_TRANSFER) {
     throw new Error('Payer and payee must be different accounts');
   }
 
-  try {
-    const client = await getConnection(CLUSTER_ENDPOINT, USER, REGION);
+  if (event.fee !== undefined) {
+    if (FEE_ACCOUNT_ID === undefined) {
+      throw new ValidationError('fee requires FEE_ACCOUNT_ID to be set on the function');
+    }
+    if (compareAccountIds(FEE_ACCOUNT_ID, event.payer_id) === 0 || compareAccountIds(FEE_ACCOUNT_ID, event.payee_id) === 0) {
+      throw new ValidationError('The fee account cannot also be the payer or payee');
+    }
+  }
 
-    // Retry loop for OCC failures
-    let attempts = 0;
-    let payerBalance: string;
+  if (event.delay_ms !== undefined) {
+    if (!CHAOS_ENABLED) {
+      throw new ValidationError('delay_ms requires CHAOS_ENABLED=true on the function');
+    }
+    if (!Number.isInteger(event.delay_ms) || event.delay_ms < 0 || event.delay_ms > MAX_DELAY_MS) {
+      throw new ValidationError(`delay_ms must be an integer between 0 and ${MAX_DELAY_MS}`);
+    }
+  }
 
-    while (true) {
-      attempts++;
+  const client = await getConnection(CLUSTER_ENDPOINT, USER, REGION);
 
-      try {
-        // Execute transaction with retry on OCC error
-        payerBalance = await client.begin(async (sql) => {
-          return await executeTransfer(sql, event);
-        });
+  const timing: TransactionTiming = { connection_ms: 0, query_ms: 0, commit_ms: 0 };
+  const { result, attempts } = await withOccRetry(client, async (sql) => {
+    const transferResult = await executeTransfer(sql, event);
+
+    if (event.server_time) {
+      const [row] = await sql`SELECT transaction_timestamp() AS server_time`;
+      transferResult.serverTime = row.server_time.toISOString();
+    }
+
+    return transferResult;
+  }, timing);
+
+  const elapsed = performance.now() - start;
+  const transactionTime = `${elapsed.toFixed(LATENCY_PRECISION)}ms`;
+
+  emitTransferMetrics(elapsed, attempts);
+
+  // Delay after the commit so no transaction is held open while sleeping
+  if (event.delay_ms) {
+    console.log(`Applying artificial delay of ${event.delay_ms}ms`);
+    await new Promise((resolve) => setTimeout(resolve, event.delay_ms));
+  }
+
+  return {
+    transfer_id: result.transferId,
+    payer_balance: result.payerBalance,
+    transaction_time: transactionTime,
+    attempts,
+    connection_wait_ms: timing.connection_ms,
+    ...(event.detailed_timing && { timing }),
+    ...(event.minor_units && {
+      payer_balance_minor: toMinorUnits(result.payerBalanceNumeric, CURRENCY_SCALE),
+      scale: CURRENCY_SCALE
+    }),
+    ...(event.include_balances && {
+      payer_balance_before: result.payerBalanceBeforeNumeric,
+      payer_balance_after: result.payerBalanceNumeric
+    }),
+    ...(result.serverTime && { server_time: result.serverTime })
+  };
+}
+
+async function resetBalances(request: ResetRequest): Promise<ResetResponse> {
+  if (request.confirm !== true) {
+    throw new ValidationError('Reset overwrites every account balance; set "confirm": true to proceed');
+  }
+
+  const client = await getConnection(CLUSTER_ENDPOINT, USER, REGION);
+
+  // Walk the primary key in batches, each committed as its own transaction
+  let rowsReset = 0;
+  let lastId: AccountId | null = null;
+
+  while (true) {
+    // A concurrent transfer can conflict with a batch; retrying it keeps the
+    // reset from stopping partway with some accounts reset and others not
+    const from = lastId;
+    const { result: rows } = await withOccRetry(client, (sql) => sql<{ id: AccountId }[]>`
+      UPDATE accounts
+      SET balance = ${request.opening_balance}
+      WHERE id IN (
+        SELECT id FROM accounts
+        WHERE ${from === null ? sql`TRUE` : sql`id > ${from}`}
+        ORDER BY id
+        LIMIT ${RESET_BATCH_SIZE}
+      )
+      RETURNING id
+    `);
+
+    if (rows.length === 0) {
+      break;
+    }
+
+    rowsReset += rows.length;
+    lastId = rows.map((row) => row.id).reduce((a, b) => (compareAccountIds(a, b) >= 0 ? a : b));
+  }
+
+  return {
+    rows_reset: rowsReset
+  };
+}
+
+async function setBalances(request: SetBalancesRequest): Promise<SetBalancesResponse> {
+  const { balances } = request;
+  if (!Array.isArray(balances) || balances.length === 0 || balances.length > RESET_BATCH_SIZE) {
+    throw new ValidationError(`balances must be a list of 1 to ${RESET_BATCH_SIZE} entries`);
+  }
+
+  // Keyed by string so ids sent as numbers match bigint ids read back as text
+  const ids = new Set<string>();
+  for (const entry of balances) {
+    validateAccountId('balances[].id', entry?.id);
+    if (ids.has(String(entry.id))) {
+      throw new ValidationError(`Duplicate id in balances: ${entry.id}`);
+    }
+    ids.add(String(entry.id));
+    validateAmount('balance', entry.balance, true);
+  }
+
+  const client = await getConnection(CLUSTER_ENDPOINT, USER, REGION);
+  const values = balances.map((entry) => [entry.id, String(entry.balance)]);
+
+  const { result, attempts } = await withOccRetry(client, async (sql) => {
+    const rows = await sql`
+      UPDATE accounts
+      SET balance = (fixture.balance)::numeric
+      FROM (VALUES ${sql(values)}) AS fixture (id, balance)
+      WHERE accounts.id = ${ACCOUNT_ID_TYPE === 'bigint' ? sql`(fixture.id)::bigint` : sql`(fixture.id)::int`}
+      RETURNING accounts.id
+    `;
+
+    // Throwing rolls back the whole batch, so no fixture is half-applied
+    if (rows.length !== ids.size) {
+      const updated = new Set(rows.map((row) => String(row.id)));
+      const unknownIds = [...ids].filter((id) => !updated.has(id));
+      throw new ValidationError(`Unknown account id(s): ${unknownIds.join(', ')}`);
+    }
+
+    return rows.length;
+  });
+
+  return {
+    rows_updated: result,
+    attempts
+  };
+}
+
+async function reverseTransfer(request: ReverseRequest): Promise<ReverseResponse> {
+  if (typeof request.transfer_id !== 'string' || !UUID_PATTERN.test(request.transfer_id)) {
+    throw new ValidationError(`transfer_id must be a UUID, received ${JSON.stringify(request.transfer_id)}`);
+  }
+
+  const client = await getConnection(CLUSTER_ENDPOINT, USER, REGION);
+
+  const { result, attempts } = await withOccRetry(client, async (sql) => {
+    const [original] = await sql`
+      SELECT id, payer_id, payee_id, amount, reversal_of, reversed_by
+      FROM transfers
+      WHERE id = ${request.transfer_id}
+    `;
+
+    if (!original) {
+      throw new ValidationError(`Transfer ${request.transfer_id} not found`);
+    }
+    if (original.reversal_of) {
+      throw new ValidationError(`Transfer ${original.id} is itself a reversal of ${original.reversal_of}`);
+    }
+    if (original.reversed_by) {
+      throw new AlreadyReversedError(`Transfer ${original.id} was already reversed by ${original.reversed_by}`);
+    }
+
+    const reversal = await executeTransfer(sql, {
+      payer_id: original.payee_id,
+      payee_id: original.payer_id,
+      amount: original.amount.toString()
+    }, original.id);
 
-        // Transaction committed successfully
-        break;
-      } catch (error) {
-        // Check if this is an OCC error (serialization failure)
-        if (isOccError(error)) {
-          // Retry on OCC error
-          continue;
+    // Writing the original row makes two concurrent reversals conflict under
+    // OCC; the retry then sees reversed_by set and refuses
+    await sql`
+      UPDATE transfers
+      SET reversed_by = ${reversal.transferId}
+      WHERE id = ${original.id}
+    `;
+
+    return { reversal, originalId: original.id as string };
+  });
+
+  return {
+    reversal_id: result.reversal.transferId,
+    original_id: result.originalId,
+    payer_balance: result.reversal.payerBalance,
+    attempts
+  };
+}
+
+async function transferHistory(request: HistoryRequest): Promise<HistoryResponse> {
+  const limit = request.limit ?? DEFAULT_HISTORY_LIMIT;
+  if (!Number.isInteger(limit) || limit < 1 || limit > MAX_HISTORY_LIMIT) {
+    throw new ValidationError(`limit must be an integer between 1 and ${MAX_HISTORY_LIMIT}`);
+  }
+
+  const client = await getConnection(CLUSTER_ENDPOINT, USER, REGION);
+
+  // Each branch is served by its own (account, created_at) index
+  const rows = await client`
+    SELECT id, payer_id, payee_id, amount, created_at FROM (
+      (SELECT id, payer_id, payee_id, amount, created_at
+       FROM transfers
+       WHERE payer_id = ${request.id}
+       ORDER BY created_at DESC
+       LIMIT ${limit})
+      UNION ALL
+      (SELECT id, payer_id, payee_id, amount, created_at
+       FROM transfers
+       WHERE payee_id = ${request.id}
+       ORDER BY created_at DESC
+       LIMIT ${limit})
+    ) AS recent
+    ORDER BY created_at DESC
+    LIMIT ${limit}
+  `;
+
+  return {
+    transfers: rows.map((row) => ({
+      id: row.id,
+      payer_id: row.payer_id,
+      payee_id: row.payee_id,
+      amount: row.amount.toString(),
+      created_at: row.created_at.toISOString()
+    }))
+  };
+}
+
+async function scheduleTransfer(request: ScheduleRequest): Promise<ScheduleResponse> {
+  if (compareAccountIds(request.payer_id, request.payee_id) === 0 && !ALLOW_SELF_TRANSFER) {
+    throw new Error('Payer and payee must be different accounts');
+  }
+
+  const executeAt = new Date(request.execute_at);
+  if (Number.isNaN(executeAt.getTime())) {
+    throw new ValidationError(`execute_at must be an ISO 8601 timestamp, got: ${request.execute_at}`);
+  }
+
+  const client = await getConnection(CLUSTER_ENDPOINT, USER, REGION);
+
+  const rows = await client`
+    INSERT INTO scheduled_transfers (payer_id, payee_id, amount, execute_at)
+    VALUES (${request.payer_id}, ${request.payee_id}, ${request.amount}, ${executeAt})
+    RETURNING id
+  `;
+
+  return {
+    scheduled_id: rows[0].id,
+    execute_at: executeAt.toISOString()
+  };
+}
+
+async function runScheduledTransfers(): Promise<RunScheduledResponse> {
+  const client = await getConnection(CLUSTER_ENDPOINT, USER, REGION);
+
+  const due = await client`
+    SELECT id, payer_id, payee_id, amount
+    FROM scheduled_transfers
+    WHERE status = 'pending' AND execute_at <= NOW()
+    ORDER BY execute_at
+    LIMIT ${SCHEDULED_BATCH_SIZE}
+  `;
+
+  let executed = 0;
+  let failed = 0;
+  let skipped = 0;
+
+  for (const scheduled of due) {
+    try {
+      const { result } = await withOccRetry(client, async (sql) => {
+        // Claim the row in the same transaction so overlapping runs can't both
+        // apply it; whichever commits second hits an OCC conflict or sees it done
+        const claimed = await sql`
+          UPDATE scheduled_transfers
+          SET status = 'done'
+          WHERE id = ${scheduled.id} AND status = 'pending'
+        `;
+
+        if (claimed.count !== 1) {
+          return null;
         }
 
-        // For non-OCC errors, rethrow
-        throw error;
+        const transferResult = await executeTransfer(sql, {
+          payer_id: scheduled.payer_id,
+          payee_id: scheduled.payee_id,
+          amount: scheduled.amount.toString()
+        });
+
+        await sql`
+          UPDATE scheduled_transfers
+          SET transfer_id = ${transferResult.transferId}
+          WHERE id = ${scheduled.id}
+        `;
+
+        return transferResult;
+      });
+
+      if (result) {
+        executed++;
+      } else {
+        skipped++;
+      }
+    } catch (error) {
+      console.error(`Scheduled transfer ${scheduled.id} failed:`, error);
+      failed++;
+
+      // Only business failures (e.g. insufficient balance) are final; the row
+      // stays pending after anything transient and is retried on the next run
+      if (!isBusinessError(error)) {
+        continue;
+      }
+
+      // A failure here must not abort the rest of the batch
+      try {
+        await client`
+          UPDATE scheduled_transfers
+          SET status = 'failed', error = ${(error as Error).message}
+          WHERE id = ${scheduled.id} AND status = 'pending'
+        `;
+      } catch (markError) {
+        console.error(`Could not mark scheduled transfer ${scheduled.id} failed:`, markError);
       }
     }
+  }
+
+  return { executed, failed, skipped };
+}
+
+async function accountBalance(request: BalanceRequest): Promise<BalanceResponse> {
+  const client = await getConnection(CLUSTER_ENDPOINT, USER, REGION);
+
+  const rows = await client`
+    SELECT balance FROM accounts
+    WHERE id = ${request.id}
+  `;
 
-    const elapsed = Date.now() - start;
-    const transactionTime = `${elapsed.toFixed(3)}ms`;
+  if (rows.length === 0) {
+    throw new Error(`Account ${request.id} not found`);
+  }
+
+  return {
+    id: request.id,
+    balance: rows[0].balance
+  };
+}
 
-    return {
-      payer_balance: payerBalance,
-      transaction_time: transactionTime,
-      attempts
-    };
+async function warmup(): Promise<WarmupResponse> {
+  const start = performance.now();
+  const client = await getConnection(CLUSTER_ENDPOINT, USER, REGION);
 
+  // An invocation runs one transaction at a time, so it only ever uses one
+  // connection; opening more would mint tokens for connections that idle out
+  // unused. The probe reopens that connection if IDLE_TIMEOUT has closed it
+  let reachedDepth = 0;
+  try {
+    await client`SELECT 1`;
+    reachedDepth = 1;
   } catch (error) {
-    console.error('Error:', error);
-    throw error;
+    console.warn('Warmup connection failed:', error);
+  }
+
+  return {
+    target_depth: 1,
+    reached_depth: reachedDepth,
+    warmup_ms: Math.round(performance.now() - start)
+  };
+}
+
+const SETUP_HINT = 'Invoke {"action": "init_schema"} to create it';
+
+async function checkSchema(client: Sql): Promise<void> {
+  const columns = await client`
+    SELECT table_name, column_name
+    FROM information_schema.columns
+    WHERE table_schema = 'public' AND table_name IN ${client(Object.keys(REQUIRED_COLUMNS))}
+  `;
+
+  for (const [table, required] of Object.entries(REQUIRED_COLUMNS)) {
+    const present = columns.filter((row) => row.table_name === table).map((row) => row.column_name);
+    if (present.length === 0) {
+      throw new SchemaError(`Table ${table} does not exist. ${SETUP_HINT}`);
+    }
+
+    const missing = required.filter((column) => !present.includes(column));
+    if (missing.length > 0) {
+      throw new SchemaError(`Table ${table} is missing column(s): ${missing.join(', ')}. See the ch05 README for the ALTER TABLE statements`);
+    }
+  }
+
+  // Without a key on id every transfer scans the table
+  const keys = await client`
+    SELECT kcu.column_name
+    FROM information_schema.table_constraints tc
+    JOIN information_schema.key_column_usage kcu
+      ON kcu.constraint_name = tc.constraint_name AND kcu.table_schema = tc.table_schema
+    WHERE tc.table_schema = 'public' AND tc.table_name = 'accounts'
+      AND tc.constraint_type IN ('PRIMARY KEY', 'UNIQUE')
+  `;
+  if (!keys.some((row) => row.column_name === 'id')) {
+    throw new SchemaError('accounts.id has no primary key, so every lookup scans the table. Recreate accounts with id as its primary key');
   }
+}
+
+async function initSchema(): Promise<InitSchemaResponse> {
+  const client = await getConnection(CLUSTER_ENDPOINT, USER, REGION);
+  const tables = Object.keys(REQUIRED_COLUMNS);
+
+  const existing = await client`
+    SELECT table_name FROM information_schema.tables
+    WHERE table_schema = 'public' AND table_name IN ${client(tables)}
+  `;
+  const existingTables = existing.map((row) => row.table_name);
+
+  // DSQL runs each DDL statement in its own transaction, so they are sent one at a time
+  for (const statement of schemaStatements(ACCOUNT_ID_TYPE === 'bigint' ? 'BIGINT' : 'INT')) {
+    await client.unsafe(statement);
+  }
+
+  return {
+    created: tables.filter((table) => !existingTables.includes(table))
+  };
+}
+
+async function listAccounts(request: AccountsRequest): Promise<AccountsResponse> {
+  const limit = request.limit ?? DEFAULT_ACCOUNTS_LIMIT;
+  if (!Number.isInteger(limit) || limit < 1 || limit > MAX_ACCOUNTS_LIMIT) {
+    throw new ValidationError(`limit must be an integer between 1 and ${MAX_ACCOUNTS_LIMIT}`);
+  }
+
+  const client = await getConnection(CLUSTER_ENDPOINT, USER, REGION);
+
+  const rows = await client`
+    SELECT id FROM accounts
+    WHERE ${request.after === undefined ? client`TRUE` : client`id > ${request.after}`}
+    ORDER BY id
+    LIMIT ${limit}
+  `;
+
+  const ids: AccountId[] = rows.map((row) => row.id);
+
+  return {
+    ids,
+    ...(ids.length === limit && { next_after: ids[ids.length - 1] })
+  };
+}
+
+async function dispatch(event: Request): Promise<Response> {
+  // Checked before any connection is made, so rejected writes never reach DSQL
+  if (MAINTENANCE_MODE && !READ_ACTIONS.includes(event.action ?? 'transfer')) {
+    throw new MaintenanceModeError('Writes are paused for maintenance; retry later with backoff');
+  }
+
+  if (SCHEMA_CHECK && !schemaChecked && event.action !== 'init_schema') {
+    await checkSchema(await getConnection(CLUSTER_ENDPOINT, USER, REGION));
+    schemaChecked = true;
+  }
+
+  switch (event.action) {
+    case 'reset':
+      return await resetBalances(event);
+
+    case 'history':
+      return await transferHistory(event);
+
+    case 'schedule':
+      return await scheduleTransfer(event);
+
+    case 'run_scheduled':
+      return await runScheduledTransfers();
+
+    case 'accounts':
+      return await listAccounts(event);
+
+    case 'set_balances':
+      return await setBalances(event);
+
+    case 'reverse':
+      return await reverseTransfer(event);
+
+    case 'balance':
+      return await accountBalance(event);
+
+    case 'warmup':
+      return await warmup();
+
+    case 'init_schema':
+      return await initSchema();
+
+    default:
+      return await transfer(event);
+  }
+}
+
+export const handler: Handler<Request, Response> = async (event, lambdaContext) => {
+  validateRequest(event);
+
+  // One trace per invocation, correlated by the Lambda request id
+  return await tracer.startActiveSpan(`ch05 ${event.action ?? 'transfer'}`, {
+    attributes: { 'faas.execution': lambdaContext.awsRequestId }
+  }, async (span) => {
+    try {
+      return await dispatch(event);
+    } catch (caught) {
+      const error = toClientError(caught);
+      console.error('Error:', caught);
+      span.recordException(error as Error);
+      span.setStatus({ code: SpanStatusCode.ERROR });
+      throw error;
+    } finally {
+      span.end();
+      // Flush before the container can be frozen. A failed export is only
+      // logged: it must never replace the result of a committed transfer
+      await tracerProvider?.forceFlush().catch((error) => console.warn('Trace export failed:', error));
+    }
+  });
 };
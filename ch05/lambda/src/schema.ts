@@ -0,0 +1,47 @@
+// DDL for every table ch05 uses. This is the only copy: the init_schema action
+// runs it, and test/conservation.ts runs it against plain PostgreSQL
+export type AccountIdColumnType = 'INT' | 'BIGINT';
+
+// One statement per entry, because DSQL runs each DDL statement in its own transaction
+export function schemaStatements(idType: AccountIdColumnType): string[] {
+  return [
+    `CREATE TABLE IF NOT EXISTS accounts (
+      id ${idType} PRIMARY KEY,
+      balance NUMERIC(18,6) NOT NULL
+    )`,
+
+    // Ledger of committed transfers
+    `CREATE TABLE IF NOT EXISTS transfers (
+      id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+      payer_id ${idType} NOT NULL,
+      payee_id ${idType} NOT NULL,
+      amount NUMERIC NOT NULL,
+      fee NUMERIC,       -- charged to the payer and credited to FEE_ACCOUNT_ID
+      created_at TIMESTAMP NOT NULL DEFAULT NOW(),
+      reversal_of UUID,  -- set on a reversal entry: the transfer it undoes
+      reversed_by UUID   -- set on an original: the reversal entry that undid it
+    )`,
+    `CREATE INDEX ASYNC IF NOT EXISTS idx_transfers_payer ON transfers(payer_id, created_at)`,
+    `CREATE INDEX ASYNC IF NOT EXISTS idx_transfers_payee ON transfers(payee_id, created_at)`,
+
+    // Future-dated transfers, executed by the run_scheduled action
+    `CREATE TABLE IF NOT EXISTS scheduled_transfers (
+      id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+      payer_id ${idType} NOT NULL,
+      payee_id ${idType} NOT NULL,
+      amount NUMERIC NOT NULL,
+      execute_at TIMESTAMP NOT NULL,
+      status VARCHAR(10) NOT NULL DEFAULT 'pending',  -- 'pending', 'done' or 'failed'
+      transfer_id UUID,
+      error TEXT
+    )`,
+    `CREATE INDEX ASYNC IF NOT EXISTS idx_scheduled_transfers_due ON scheduled_transfers(status, execute_at)`,
+  ];
+}
+
+// Tables the handler depends on, with the columns it reads or writes
+export const REQUIRED_COLUMNS: Record<string, string[]> = {
+  accounts: ['id', 'balance'],
+  transfers: ['id', 'payer_id', 'payee_id', 'amount', 'fee', 'created_at', 'reversal_of', 'reversed_by'],
+  scheduled_transfers: ['id', 'payer_id', 'payee_id', 'amount', 'execute_at', 'status', 'transfer_id', 'error'],
+};
@@ -1,3 +1,5 @@
+-- Tables are created by the init_schema action; their DDL lives in
+-- lambda/src/schema.ts so the handler and the integration test share one copy
 DELETE FROM accounts;
 
 INSERT INTO accounts (id, balance)
@@ -1,6 +1,7 @@
 import { Handler } from 'aws-lambda';
 import { DsqlSigner } from '@aws-sdk/dsql-signer';
 import postgres, { Sql, PostgresError } from 'postgres';
+import { lookup } from 'dns/promises';
 
 interface Request {
   payer_id: string;  // UUID
@@ -18,6 +19,23 @@ const CLUSTER_ENDPOINT = process.env.CLUSTER_ENDPOINT || 'YOUR_CLUSTER_ENDPOINT'
 const REGION = process.env.REGION || 'us-west-2';
 const USER = 'admin';
 
+// Decimal places in the reported transaction_time; checked at load so a bad
+// value can't fail an invocation after its transaction has committed
+const LATENCY_PRECISION = Number(process.env.LATENCY_PRECISION || 3);
+
+if (!Number.isInteger(LATENCY_PRECISION) || LATENCY_PRECISION < 0 || LATENCY_PRECISION > 9) {
+  throw new Error('LATENCY_PRECISION must be an integer between 0 and 9');
+}
+
+// Initial connect is retried so a transient blip doesn't fail the cold start
+const INIT_CONNECT_ATTEMPTS = 5;
+const INIT_CONNECT_BASE_DELAY_MS = 100;
+
+// Configuration problems surface with errorType `EndpointConfigError`
+class EndpointConfigError extends Error {
+  name = 'EndpointConfigError';
+}
+
 // Connection reuse - create once and reuse across invocations
 let cachedClient: Sql | null = null;
 
@@ -35,12 +53,50 @@ async function getPasswordToken(clusterEndpoint: string, user: string, region: s
   }
 }
 
+async function validateEndpoint(clusterEndpoint: string, region: string): Promise<void> {
+  if (!clusterEndpoint || clusterEndpoint === 'YOUR_CLUSTER_ENDPOINT') {
+    throw new EndpointConfigError(
+      'CLUSTER_ENDPOINT is not set. Deploy with -c clusterEndpoint=<cluster-id>.dsql.<region>.on.aws ' +
+      'or set the CLUSTER_ENDPOINT environment variable on the function'
+    );
+  }
+
+  try {
+    await lookup(clusterEndpoint);
+  } catch (error) {
+    throw new EndpointConfigError(
+      `Cannot resolve cluster endpoint ${clusterEndpoint} (${(error as Error).message}). ` +
+      `Check that CLUSTER_ENDPOINT names an existing cluster in ${region}`
+    );
+  }
+}
+
+async function connectWithRetry(client: Sql): Promise<void> {
+  for (let attempt = 1; ; attempt++) {
+    try {
+      await client`SELECT 1`;
+      return;
+    } catch (error) {
+      if (attempt >= INIT_CONNECT_ATTEMPTS) {
+        throw error;
+      }
+
+      const delay = INIT_CONNECT_BASE_DELAY_MS * 2 ** (attempt - 1);
+      console.warn(`Connection attempt ${attempt}/${INIT_CONNECT_ATTEMPTS} failed, retrying in ${delay}ms:`, error);
+      await new Promise((resolve) => setTimeout(resolve, delay));
+    }
+  }
+}
+
 async function getConnection(clusterEndpoint: string, user: string, region: string): Promise<Sql> {
   if (cachedClient) {
     return cachedClient;
   }
 
-  cachedClient = postgres({
+  // Fail with an actionable message on cold start instead of a raw connection error
+  await validateEndpoint(clusterEndpoint, region);
+
+  const client = postgres({
     host: clusterEndpoint,
     user: user,
     password: async () => await getPasswordToken(clusterEndpoint, user, region),
@@ -52,6 +108,18 @@ async function getConnection(clusterEndpoint: string, user: string, region: stri
     }
   });
 
+  // Only a failure that outlasts every retry is reported as configuration
+  try {
+    await connectWithRetry(client);
+  } catch (error) {
+    await client.end();
+    throw new EndpointConfigError(
+      `Cannot connect to cluster endpoint ${clusterEndpoint} after ${INIT_CONNECT_ATTEMPTS} attempts (${(error as Error).message}). ` +
+      'Check that the cluster is active and the function role has dsql:DbConnectAdmin (see add-dsql-permissions.sh)'
+    );
+  }
+
+  cachedClient = client;
   return cachedClient;
 }
 
@@ -93,7 +161,8 @@ async function executeTransfer(sql: Sql, request: Request): Promise<string> {
 }
 
 export const handler: Handler<Request, Response> = async (event) => {
-  const start = Date.now();
+  // performance.now() has sub-millisecond resolution, unlike Date.now()
+  const start = performance.now();
 
   if (event.payer_id === event.payee_id) {
     throw new Error('Payer and payee must be different accounts');
@@ -129,8 +198,8 @@ export const handler: Handler<Request, Response> = async (event) => {
       }
     }
 
-    const elapsed = Date.now() - start;
-    const transactionTime = `${elapsed.toFixed(3)}ms`;
+    const elapsed = performance.now() - start;
+    const transactionTime = `${elapsed.toFixed(LATENCY_PRECISION)}ms`;
 
     return {
       payer_balance: payerBalance,